@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::ffi::c_float;
 use std::os::raw::c_int;
 use std::slice;
@@ -13,11 +14,73 @@ pub struct CompressorSettings {
     release_ms: f32,
     knee_db: f32,
     makeup_gain_db: f32,
+    lookahead_ms: f32,
+    detector_hpf_hz: f32,
+    detector_hpf_q: f32,
 }
 
 #[derive(Default)]
 struct CompressorState {
     envelope: f32,
+    envelope_sq: f32,
+}
+
+// Peak follows instantaneous `abs(sample)`; RMS follows a smoothed
+// mean-square and takes the square root, which tracks perceived/sustained
+// level rather than transients.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DetectionMode {
+    Peak = 0,
+    Rms = 1,
+}
+
+impl DetectionMode {
+    fn from_c_int(value: c_int) -> Self {
+        match value {
+            1 => DetectionMode::Rms,
+            _ => DetectionMode::Peak,
+        }
+    }
+}
+
+// Smooths `sample` into an envelope using the Peak or RMS detector selected
+// by `mode`, applying `alpha_att`/`alpha_rel` depending on whether the
+// detector is rising or falling, and returns the envelope in linear scale.
+fn detect_envelope(
+    mode: DetectionMode,
+    sample: f32,
+    envelope: &mut f32,
+    envelope_sq: &mut f32,
+    alpha_att: f32,
+    alpha_rel: f32,
+) -> f32 {
+    match mode {
+        DetectionMode::Peak => {
+            let abs_sample = sample.abs();
+            *envelope = if abs_sample > *envelope {
+                alpha_att * *envelope + (1.0 - alpha_att) * abs_sample
+            } else {
+                alpha_rel * *envelope + (1.0 - alpha_rel) * abs_sample
+            };
+            if *envelope < 0.0 {
+                *envelope = 0.0;
+            }
+            *envelope
+        }
+        DetectionMode::Rms => {
+            let sq = sample * sample;
+            *envelope_sq = if sq > *envelope_sq {
+                alpha_att * *envelope_sq + (1.0 - alpha_att) * sq
+            } else {
+                alpha_rel * *envelope_sq + (1.0 - alpha_rel) * sq
+            };
+            if *envelope_sq < 0.0 {
+                *envelope_sq = 0.0;
+            }
+            envelope_sq.sqrt()
+        }
+    }
 }
 
 fn db_to_linear(db: f32) -> f32 {
@@ -36,6 +99,40 @@ fn clamp_f32(val: f32, min: f32, max: f32) -> f32 {
     val.max(min).min(max)
 }
 
+// Normalized biquad coefficients (a0 already divided out) shared by every
+// Direct-Form-I filter stage in this crate.
+#[derive(Clone, Copy, Default)]
+struct BiquadCoeffs {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+#[derive(Default)]
+struct BiquadState {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl BiquadState {
+    fn process(&mut self, coeffs: &BiquadCoeffs, input: f32) -> f32 {
+        let output = coeffs.b0 * input + coeffs.b1 * self.x1 + coeffs.b2 * self.x2
+            - coeffs.a1 * self.y1
+            - coeffs.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+
+        output
+    }
+}
+
 // Resampler function using linear interpolation (for simplicity)
 // In a production environment, you'd want to use a more sophisticated algorithm
 fn resample(input: &[f32], src_rate: f32, dst_rate: f32) -> Vec<f32> {
@@ -72,20 +169,26 @@ pub extern "C" fn ProcessCompressor(
     knee_db: c_float,
     makeup_gain_db: c_float,
     mix: c_float,
+    lookahead_ms: c_float,
+    detector_hpf_hz: c_float,
+    detector_hpf_q: c_float,
+    detection_mode: c_int,
+    channels: c_int,
+    planar: c_int,
+    gr_out_ptr: *mut c_float,
 ) {
     let length = length as usize;
     let sample_rate = sample_rate as f32;
     let mix = mix as f32;
+    let detection_mode = DetectionMode::from_c_int(detection_mode);
+    let channels = channels.max(1) as usize;
+    let interleaved = planar == 0;
 
-    if length == 0 || waveform_ptr.is_null() {
+    if length == 0 || waveform_ptr.is_null() || length % channels != 0 {
         return;
     }
 
     let waveform = unsafe { slice::from_raw_parts_mut(waveform_ptr, length) };
-    let mut original_waveform = vec![0.0f32; length];
-    if mix < 1.0 - EPSILON {
-        original_waveform.clone_from_slice(waveform);
-    }
 
     let settings = CompressorSettings {
         threshold_db,
@@ -94,6 +197,9 @@ pub extern "C" fn ProcessCompressor(
         release_ms,
         knee_db,
         makeup_gain_db,
+        lookahead_ms,
+        detector_hpf_hz,
+        detector_hpf_q,
     };
 
     let makeup_gain_lin = db_to_linear(settings.makeup_gain_db);
@@ -106,23 +212,85 @@ pub extern "C" fn ProcessCompressor(
     let alpha_att = (-1.0 / attack_samples as f64).exp() as f32;
     let alpha_rel = (-1.0 / release_samples as f64).exp() as f32;
 
-    let mut state = CompressorState::default();
+    // Frames (one sample per channel) rather than raw samples, so the
+    // detector and its delay/flush logic run once per frame and the same
+    // gain is applied to every channel, keeping the stereo image stable.
+    let frames = length / channels;
+    let frame_index = |frame: usize, channel: usize| -> usize {
+        if interleaved {
+            frame * channels + channel
+        } else {
+            channel * frames + frame
+        }
+    };
 
-    for i in 0..length {
-        let input_sample = waveform[i];
-        let abs_input = input_sample.abs();
+    // Delay line for look-ahead: the detector reads the sample arriving "now",
+    // while the gain it computes is applied to a sample `lookahead_samples`
+    // behind, so the gain ramp has already started by the time a transient
+    // actually reaches the output. A zero-length delay line degenerates to
+    // the original instantaneous behavior. Each channel gets its own delay
+    // line so every channel's audio is preserved, even though they all share
+    // one detector/gain curve.
+    let lookahead_samples = ((settings.lookahead_ms.max(0.0) / 1000.0) * sample_rate).round() as usize;
+    let lookahead_samples = lookahead_samples.min(frames);
+    let mut delay_lines: Vec<VecDeque<f32>> = (0..channels)
+        .map(|_| VecDeque::from(vec![0.0f32; lookahead_samples]))
+        .collect();
+
+    let mut dry_waveform = vec![0.0f32; length];
+    let mut wet_waveform = vec![0.0f32; length];
+    // Per-sample gain-reduction meter in dB, same shape as waveform, copied
+    // to `gr_out_ptr` at the end if the caller provided one.
+    let mut gr_waveform = vec![0.0f32; length];
+
+    // Optional sidechain high-pass on the detector only: the delayed/gained
+    // audio path always uses the unfiltered sample, only the envelope
+    // follower sees the high-passed version. Each channel is filtered with
+    // its own state before the channels are combined into one detector input.
+    let detector_hpf_coeffs = rbj_highpass_coeffs(
+        settings.detector_hpf_hz.max(1.0),
+        settings.detector_hpf_q.max(0.1),
+        sample_rate,
+    );
+    let mut detector_hpf_states: Vec<BiquadState> =
+        (0..channels).map(|_| BiquadState::default()).collect();
 
-        state.envelope = if abs_input > state.envelope {
-            alpha_att * state.envelope + (1.0 - alpha_att) * abs_input
-        } else {
-            alpha_rel * state.envelope + (1.0 - alpha_rel) * abs_input
-        };
+    let mut state = CompressorState::default();
+    let mut raw_samples = vec![0.0f32; channels];
 
-        if state.envelope < 0.0 {
-            state.envelope = 0.0;
+    for frame in 0..frames + lookahead_samples {
+        for (c, raw_sample) in raw_samples.iter_mut().enumerate() {
+            *raw_sample = if frame < frames {
+                waveform[frame_index(frame, c)]
+            } else {
+                0.0
+            };
         }
 
-        let env_db = linear_to_db_safe(state.envelope);
+        // Shared detector: the max absolute value across channels drives a
+        // single envelope/gain curve applied to all of them.
+        let detect_input = raw_samples
+            .iter()
+            .enumerate()
+            .map(|(c, &sample)| {
+                if settings.detector_hpf_hz > EPSILON {
+                    detector_hpf_states[c].process(&detector_hpf_coeffs, sample)
+                } else {
+                    sample
+                }
+            })
+            .fold(0.0f32, |max_abs, filtered| max_abs.max(filtered.abs()));
+
+        let envelope = detect_envelope(
+            detection_mode,
+            detect_input,
+            &mut state.envelope,
+            &mut state.envelope_sq,
+            alpha_att,
+            alpha_rel,
+        );
+
+        let env_db = linear_to_db_safe(envelope);
         let overshoot = env_db - settings.threshold_db;
 
         let mut gain_reduction_db = 0.0;
@@ -160,13 +328,32 @@ pub extern "C" fn ProcessCompressor(
             gain_multiplier = 1.0;
         }
 
-        waveform[i] = input_sample * gain_multiplier * makeup_gain_lin;
+        let out_frame = frame as isize - lookahead_samples as isize;
+
+        for (c, &raw_sample) in raw_samples.iter().enumerate() {
+            delay_lines[c].push_back(raw_sample);
+            let delayed_sample = delay_lines[c].pop_front().unwrap_or(0.0);
+
+            if out_frame >= 0 {
+                let idx = frame_index(out_frame as usize, c);
+                dry_waveform[idx] = delayed_sample;
+                wet_waveform[idx] = delayed_sample * gain_multiplier * makeup_gain_lin;
+                gr_waveform[idx] = -gain_reduction_db;
+            }
+        }
+    }
+
+    if !gr_out_ptr.is_null() {
+        let gr_out = unsafe { slice::from_raw_parts_mut(gr_out_ptr, length) };
+        gr_out.copy_from_slice(&gr_waveform);
     }
 
     if mix < 1.0 - EPSILON {
         for i in 0..length {
-            waveform[i] = original_waveform[i] * (1.0 - mix) + waveform[i] * mix;
+            waveform[i] = dry_waveform[i] * (1.0 - mix) + wet_waveform[i] * mix;
         }
+    } else {
+        waveform.copy_from_slice(&wet_waveform);
     }
 
     for sample in waveform.iter_mut() {
@@ -174,6 +361,329 @@ pub extern "C" fn ProcessCompressor(
     }
 }
 
+// K-weighting pre-filter stage 1: a high-shelf boost above ~1.5 kHz that
+// approximates the head diffraction/resonance response used by ITU-R BS.1770.
+fn k_weighting_shelf_coeffs(sample_rate: f32) -> BiquadCoeffs {
+    let fc = 1500.0_f32;
+    let gain_db = 4.0_f32;
+    let q = std::f32::consts::FRAC_1_SQRT_2;
+
+    let a = 10.0_f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f32::consts::PI * fc / sample_rate;
+    let (sin_w0, cos_w0) = (w0.sin(), w0.cos());
+    let alpha = sin_w0 / (2.0 * q);
+    let two_sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+    let b0 = a * ((a + 1.0) + (a - 1.0) * cos_w0 + two_sqrt_a_alpha);
+    let b1 = -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0);
+    let b2 = a * ((a + 1.0) + (a - 1.0) * cos_w0 - two_sqrt_a_alpha);
+    let a0 = (a + 1.0) - (a - 1.0) * cos_w0 + two_sqrt_a_alpha;
+    let a1 = 2.0 * ((a - 1.0) - (a + 1.0) * cos_w0);
+    let a2 = (a + 1.0) - (a - 1.0) * cos_w0 - two_sqrt_a_alpha;
+
+    BiquadCoeffs {
+        b0: b0 / a0,
+        b1: b1 / a0,
+        b2: b2 / a0,
+        a1: a1 / a0,
+        a2: a2 / a0,
+    }
+}
+
+// Standard RBJ Audio EQ Cookbook high-pass biquad: `w0 = 2*pi*fc/fs`,
+// `alpha = sin(w0)/(2*Q)`, normalized by `a0`.
+fn rbj_highpass_coeffs(fc: f32, q: f32, sample_rate: f32) -> BiquadCoeffs {
+    let w0 = 2.0 * std::f32::consts::PI * fc / sample_rate;
+    let (sin_w0, cos_w0) = (w0.sin(), w0.cos());
+    let alpha = sin_w0 / (2.0 * q);
+
+    let b0 = (1.0 + cos_w0) / 2.0;
+    let b1 = -(1.0 + cos_w0);
+    let b2 = (1.0 + cos_w0) / 2.0;
+    let a0 = 1.0 + alpha;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha;
+
+    BiquadCoeffs {
+        b0: b0 / a0,
+        b1: b1 / a0,
+        b2: b2 / a0,
+        a1: a1 / a0,
+        a2: a2 / a0,
+    }
+}
+
+// K-weighting pre-filter stage 2: a high-pass around ~38 Hz that removes
+// sub-sonic energy before loudness is integrated.
+fn k_weighting_highpass_coeffs(sample_rate: f32) -> BiquadCoeffs {
+    rbj_highpass_coeffs(38.0, 0.5, sample_rate)
+}
+
+const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+const RELATIVE_GATE_OFFSET_LU: f32 = 10.0;
+
+fn loudness_from_mean_square(mean_square: f32) -> f32 {
+    -0.691 + 10.0 * mean_square.max(MIN_LINEAR_VALUE).log10()
+}
+
+// EBU R128 / ITU-R BS.1770 integrated loudness: K-weight the whole signal,
+// then gate 400ms blocks (stepped every 100ms) first against an absolute
+// -70 LUFS floor and then against a relative gate 10 LU below the mean of
+// the surviving blocks.
+fn measure_integrated_loudness(samples: &[f32], sample_rate: f32) -> f32 {
+    let shelf_coeffs = k_weighting_shelf_coeffs(sample_rate);
+    let highpass_coeffs = k_weighting_highpass_coeffs(sample_rate);
+    let mut shelf_state = BiquadState::default();
+    let mut highpass_state = BiquadState::default();
+
+    let filtered: Vec<f32> = samples
+        .iter()
+        .map(|&s| {
+            let shelved = shelf_state.process(&shelf_coeffs, s);
+            highpass_state.process(&highpass_coeffs, shelved)
+        })
+        .collect();
+
+    let block_samples = ((0.4 * sample_rate).round() as usize).max(1);
+    let step_samples = ((0.1 * sample_rate).round() as usize).max(1);
+
+    if filtered.len() < block_samples {
+        let mean_square = filtered.iter().map(|&s| s * s).sum::<f32>() / filtered.len() as f32;
+        return loudness_from_mean_square(mean_square);
+    }
+
+    let mut block_powers = Vec::new();
+    let mut start = 0;
+    while start + block_samples <= filtered.len() {
+        let block = &filtered[start..start + block_samples];
+        let mean_square = block.iter().map(|&s| s * s).sum::<f32>() / block_samples as f32;
+        block_powers.push(mean_square);
+        start += step_samples;
+    }
+
+    let absolute_gated: Vec<f32> = block_powers
+        .iter()
+        .copied()
+        .filter(|&p| loudness_from_mean_square(p) > ABSOLUTE_GATE_LUFS)
+        .collect();
+
+    if absolute_gated.is_empty() {
+        return ABSOLUTE_GATE_LUFS;
+    }
+
+    let mean_power = absolute_gated.iter().sum::<f32>() / absolute_gated.len() as f32;
+    let relative_gate_lufs = loudness_from_mean_square(mean_power) - RELATIVE_GATE_OFFSET_LU;
+
+    let relatively_gated: Vec<f32> = absolute_gated
+        .iter()
+        .copied()
+        .filter(|&p| loudness_from_mean_square(p) > relative_gate_lufs)
+        .collect();
+
+    if relatively_gated.is_empty() {
+        return loudness_from_mean_square(mean_power);
+    }
+
+    let final_mean_power = relatively_gated.iter().sum::<f32>() / relatively_gated.len() as f32;
+    loudness_from_mean_square(final_mean_power)
+}
+
+#[no_mangle]
+pub extern "C" fn ProcessLoudnorm(
+    waveform_ptr: *const c_float,
+    length: c_int,
+    sample_rate: c_int,
+    target_lufs: c_float,
+) -> *mut ProcessingResult {
+    if waveform_ptr.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let length = length as usize;
+    if length == 0 {
+        return std::ptr::null_mut();
+    }
+
+    let sample_rate = sample_rate as f32;
+    let waveform = unsafe { slice::from_raw_parts(waveform_ptr, length) };
+
+    let integrated_lufs = measure_integrated_loudness(waveform, sample_rate);
+    let gain_lin = db_to_linear(target_lufs - integrated_lufs);
+
+    let mut output_audio: Vec<f32> = waveform.iter().map(|&s| s * gain_lin).collect();
+
+    let result = Box::new(ProcessingResult {
+        audio_ptr: output_audio.as_mut_ptr(),
+        length: output_audio.len() as c_int,
+        sample_rate: sample_rate as c_int,
+        gr_ptr: std::ptr::null_mut(),
+        gr_length: 0,
+        _audio_data: output_audio,
+        _gr_data: Vec::new(),
+    });
+
+    Box::into_raw(result)
+}
+
+const TRUE_PEAK_OVERSAMPLE_FACTOR: usize = 4;
+// Per BS.1770-4 Annex 2, 12 taps/phase gives the interpolation filter enough
+// taps to keep its passband flat almost up to the original Nyquist edge.
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+const TRUE_PEAK_KAISER_BETA: f64 = 8.0;
+
+// Zeroth-order modified Bessel function of the first kind, via its power
+// series, for the Kaiser window.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0f64;
+    let mut term = 1.0f64;
+    let half_x = x / 2.0;
+    for k in 1..30 {
+        term *= (half_x / k as f64).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+fn kaiser_window(n: usize, total_taps: usize, beta: f64) -> f64 {
+    let alpha = (total_taps - 1) as f64 / 2.0;
+    let ratio = (n as f64 - alpha) / alpha;
+    let inner = (1.0 - ratio * ratio).max(0.0);
+    bessel_i0(beta * inner.sqrt()) / bessel_i0(beta)
+}
+
+// Kaiser-windowed-sinc low-pass prototype split into polyphase components for
+// 4x oversampled interpolation. This is only used to *look for* inter-sample
+// (true) peaks, never to actually resample the audio that gets written back.
+fn true_peak_polyphase_fir() -> Vec<Vec<f32>> {
+    let total_taps = TRUE_PEAK_OVERSAMPLE_FACTOR * TRUE_PEAK_TAPS_PER_PHASE;
+    let cutoff = 1.0 / TRUE_PEAK_OVERSAMPLE_FACTOR as f64;
+    let center = (total_taps - 1) as f64 / 2.0;
+
+    let mut prototype = vec![0.0f64; total_taps];
+    for (n, coeff) in prototype.iter_mut().enumerate() {
+        let x = n as f64 - center;
+        let sinc = if x.abs() < 1e-9 {
+            cutoff
+        } else {
+            let px = std::f64::consts::PI * cutoff * x;
+            cutoff * px.sin() / px
+        };
+        *coeff = sinc * kaiser_window(n, total_taps, TRUE_PEAK_KAISER_BETA);
+    }
+
+    let mut phases =
+        vec![vec![0.0f64; TRUE_PEAK_TAPS_PER_PHASE]; TRUE_PEAK_OVERSAMPLE_FACTOR];
+    for (n, &coeff) in prototype.iter().enumerate() {
+        let phase = n % TRUE_PEAK_OVERSAMPLE_FACTOR;
+        let tap = n / TRUE_PEAK_OVERSAMPLE_FACTOR;
+        phases[phase][tap] = coeff;
+    }
+
+    // Normalize each phase to unit DC/passband gain so a full-scale DC or
+    // low-frequency signal reconstructs back to its own level instead of
+    // reporting a false overshoot.
+    for phase_coeffs in &mut phases {
+        let phase_sum: f64 = phase_coeffs.iter().sum();
+        if phase_sum.abs() > 1e-12 {
+            for coeff in phase_coeffs.iter_mut() {
+                *coeff /= phase_sum;
+            }
+        }
+    }
+
+    phases
+        .into_iter()
+        .map(|p| p.into_iter().map(|c| c as f32).collect())
+        .collect()
+}
+
+// Finds the maximum reconstructed (inter-sample) magnitude of `waveform` by
+// interpolating `TRUE_PEAK_OVERSAMPLE_FACTOR` extra points between every pair
+// of samples and tracking the largest magnitude seen, sample-domain peaks
+// included. Samples beyond either edge are clamped to the nearest in-range
+// sample (not zero-padded) so a constant/near-edge signal doesn't lose the
+// negative side-lobe contributions that keep the filter's gain at unity.
+fn max_true_peak(waveform: &[f32]) -> f32 {
+    let phases = true_peak_polyphase_fir();
+    let half_span = (TRUE_PEAK_TAPS_PER_PHASE / 2) as isize;
+    let last_idx = waveform.len() as isize - 1;
+    let mut peak = 0.0f32;
+
+    for i in 0..waveform.len() {
+        peak = peak.max(waveform[i].abs());
+
+        for phase_coeffs in &phases {
+            let mut acc = 0.0f32;
+            for (tap, &coeff) in phase_coeffs.iter().enumerate() {
+                let idx = (i as isize - tap as isize + half_span).clamp(0, last_idx);
+                acc += coeff * waveform[idx as usize];
+            }
+            peak = peak.max(acc.abs());
+        }
+    }
+
+    peak
+}
+
+// Gathers one channel's samples out of an interleaved or planar multi-channel
+// buffer so true-peak interpolation never runs across adjacent channels.
+fn extract_channel(
+    waveform: &[f32],
+    channels: usize,
+    frames: usize,
+    channel: usize,
+    interleaved: bool,
+) -> Vec<f32> {
+    (0..frames)
+        .map(|frame| {
+            let idx = if interleaved {
+                frame * channels + channel
+            } else {
+                channel * frames + frame
+            };
+            waveform[idx]
+        })
+        .collect()
+}
+
+#[no_mangle]
+pub extern "C" fn ProcessTruePeakLimit(
+    waveform_ptr: *mut c_float,
+    length: c_int,
+    max_true_peak_db: c_float,
+    channels: c_int,
+    planar: c_int,
+) {
+    let length = length as usize;
+    let channels = channels.max(1) as usize;
+    let interleaved = planar == 0;
+
+    if length == 0 || waveform_ptr.is_null() || length % channels != 0 {
+        return;
+    }
+
+    let waveform = unsafe { slice::from_raw_parts_mut(waveform_ptr, length) };
+    let frames = length / channels;
+
+    // One shared true-peak reading across all channels (like the linked
+    // detector in ProcessCompressor/ProcessDucking), each channel measured
+    // independently so interpolation never mixes adjacent channels.
+    let true_peak = (0..channels)
+        .map(|c| {
+            let channel_samples = extract_channel(waveform, channels, frames, c, interleaved);
+            max_true_peak(&channel_samples)
+        })
+        .fold(0.0f32, f32::max);
+    let ceiling_lin = db_to_linear(max_true_peak_db);
+
+    if true_peak > ceiling_lin && true_peak > EPSILON {
+        let attenuation = ceiling_lin / true_peak;
+        for sample in waveform.iter_mut() {
+            *sample *= attenuation;
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn ProcessDucking(
     main_audio_ptr: *mut c_float,
@@ -184,19 +694,27 @@ pub extern "C" fn ProcessDucking(
     sidechain_length: c_int,
     sidechain_sample_rate: c_int,
     sidechain_gain_db: c_float,
+    sidechain_hpf_hz: c_float,
+    sidechain_hpf_q: c_float,
     threshold_db: c_float,
     reduction_db: c_float,
     attack_ms: c_float,
     release_ms: c_float,
+    detection_mode: c_int,
+    channels: c_int,
+    planar: c_int,
 ) -> *mut ProcessingResult {
     if main_audio_ptr.is_null() || sidechain_audio_ptr.is_null() {
         return std::ptr::null_mut();
     }
 
+    let detection_mode = DetectionMode::from_c_int(detection_mode);
+    let channels = channels.max(1) as usize;
+    let interleaved = planar == 0;
     let main_length = main_length as usize;
     let sidechain_length = sidechain_length as usize;
-    
-    if main_length == 0 || sidechain_length == 0 {
+
+    if main_length == 0 || sidechain_length == 0 || main_length % channels != 0 {
         return std::ptr::null_mut();
     }
 
@@ -220,16 +738,34 @@ pub extern "C" fn ProcessDucking(
         sidechain_audio = resample(&sidechain_audio, sidechain_sample_rate, main_sample_rate);
     }
 
-    // Determine processing length and prepare buffers
-    let process_length = main_audio.len();
+    // Determine processing length and prepare buffers. A "frame" is one
+    // sample per channel; the shared detector produces one gain value per
+    // frame which is then applied to every channel in that frame, keeping
+    // the stereo (or multi-channel) image stable under compression.
+    let main_frames = main_audio.len() / channels;
     let sidechain_processed_length = sidechain_audio.len();
-    
+
+    // frame_index maps a (frame, channel) pair to its position in the
+    // interleaved or planar main buffer.
+    let frame_index = |frame: usize, channel: usize| -> usize {
+        if interleaved {
+            frame * channels + channel
+        } else {
+            channel * main_frames + frame
+        }
+    };
+
     // Add fade-out to avoid sudden release at end of audio
     let fade_out_samples = (release_ms / 1000.0 * main_sample_rate) as usize;
-    
+
     // Create a new buffer for the processed audio
     let mut output_audio = main_audio.clone();
 
+    // Per-sample gain-reduction meter in dB, same shape as output_audio, so a
+    // UI can draw a real-time gain-reduction curve without re-running the
+    // detector. Defaults to 0 dB (no reduction) outside the ducked region.
+    let mut gr_data = vec![0.0f32; output_audio.len()];
+
     let settings = DuckCompressorSettings {
         threshold_db,
         reduction_db: -reduction_db.abs(), // Ensure reduction is positive since we're reducing gain
@@ -256,29 +792,41 @@ pub extern "C" fn ProcessDucking(
 
     let mut state = DuckCompressorState::default();
 
+    // Optional sidechain high-pass, detector-only: low-frequency energy
+    // (plosives, rumble, music bass) is filtered out before it reaches the
+    // envelope follower, but the sidechain audio mixed into output_audio
+    // below stays unfiltered.
+    let sidechain_hpf_coeffs = rbj_highpass_coeffs(
+        sidechain_hpf_hz.max(1.0),
+        sidechain_hpf_q.max(0.1),
+        main_sample_rate,
+    );
+    let mut sidechain_hpf_state = BiquadState::default();
+
     // Processing loop - only up to the minimum of both lengths
-    let overlap_length = process_length.min(sidechain_processed_length);
+    let overlap_length = main_frames.min(sidechain_processed_length);
 
     // Process overlapping portion with ducking
-    for i in 0..overlap_length {
+    for frame in 0..overlap_length {
         // Extract the sidechain signal level (voice)
-        let sidechain_sample = sidechain_audio[i];
-        let abs_sidechain = sidechain_sample.abs();
-
-        // Envelope follower on sidechain signal (similar to peak detector)
-        state.envelope = if abs_sidechain > state.envelope {
-            alpha_att * state.envelope + (1.0 - alpha_att) * abs_sidechain
+        let sidechain_sample = sidechain_audio[frame];
+        let detect_sample = if sidechain_hpf_hz > EPSILON {
+            sidechain_hpf_state.process(&sidechain_hpf_coeffs, sidechain_sample)
         } else {
-            alpha_rel * state.envelope + (1.0 - alpha_rel) * abs_sidechain
+            sidechain_sample
         };
-
-        // Ensure envelope stays positive
-        if state.envelope < 0.0 {
-            state.envelope = 0.0;
-        }
+        // Envelope follower on sidechain signal (Peak or RMS, per detection_mode)
+        let envelope = detect_envelope(
+            detection_mode,
+            detect_sample,
+            &mut state.envelope,
+            &mut state.envelope_sq,
+            alpha_att,
+            alpha_rel,
+        );
 
         // Determine gain reduction amount based on sidechain level
-        let target_gain_reduction = if state.envelope > threshold_lin {
+        let target_gain_reduction = if envelope > threshold_lin {
             // Above threshold - apply ducking
             reduction_lin
         } else {
@@ -295,32 +843,46 @@ pub extern "C" fn ProcessDucking(
             state.gain_reduction = alpha_rel * state.gain_reduction + (1.0 - alpha_rel) * target_gain_reduction;
         }
 
-        // Apply gain reduction to main signal
-        output_audio[i] = main_audio[i] * state.gain_reduction + sidechain_audio[i];
+        // Apply gain reduction to main signal, same multiplier on every
+        // channel of this frame, and mix in the (mono) sidechain signal
+        let gr_db = linear_to_db_safe(state.gain_reduction);
+        for c in 0..channels {
+            let idx = frame_index(frame, c);
+            output_audio[idx] = main_audio[idx] * state.gain_reduction + sidechain_sample;
+            gr_data[idx] = gr_db;
+        }
     }
 
     // Apply gentle release for the remainder of the main audio after sidechain ends
-    if process_length > overlap_length {
+    if main_frames > overlap_length {
         // Get the final gain reduction value at the end of the sidechain
         let final_gain_reduction = state.gain_reduction;
-        
-        // Calculate how many samples to fade out (bounded by remaining samples)
-        let fade_out_length = fade_out_samples.min( process_length - overlap_length);
-        
+
+        // Calculate how many frames to fade out (bounded by remaining frames)
+        let fade_out_length = fade_out_samples.min(main_frames - overlap_length);
+
         for i in 0..fade_out_length {
             // Linearly interpolate from final_gain_reduction to 1.0
             let progress = i as f32 / fade_out_length as f32;
             let current_reduction = final_gain_reduction + (1.0 - final_gain_reduction) * progress;
-            
-            // Apply the fading gain reduction
-            let idx = overlap_length + i;
-            output_audio[idx] = main_audio[idx] * current_reduction;
+
+            // Apply the fading gain reduction to every channel of this frame
+            let frame = overlap_length + i;
+            let gr_db = linear_to_db_safe(current_reduction);
+            for c in 0..channels {
+                let idx = frame_index(frame, c);
+                output_audio[idx] = main_audio[idx] * current_reduction;
+                gr_data[idx] = gr_db;
+            }
         }
-        
+
         // Copy any remaining audio unchanged
-        if overlap_length + fade_out_length < process_length {
-            for i in (overlap_length + fade_out_length)..process_length {
-                output_audio[i] = main_audio[i];
+        if overlap_length + fade_out_length < main_frames {
+            for frame in (overlap_length + fade_out_length)..main_frames {
+                for c in 0..channels {
+                    let idx = frame_index(frame, c);
+                    output_audio[idx] = main_audio[idx];
+                }
             }
         }
     }
@@ -330,7 +892,10 @@ pub extern "C" fn ProcessDucking(
         audio_ptr: output_audio.as_mut_ptr(),
         length: output_audio.len() as c_int,
         sample_rate: main_sample_rate as c_int,
+        gr_ptr: gr_data.as_mut_ptr(),
+        gr_length: gr_data.len() as c_int,
         _audio_data: output_audio, // Keep the Vec alive
+        _gr_data: gr_data,         // Keep the gain-reduction meter alive
     });
 
     Box::into_raw(result)
@@ -340,6 +905,7 @@ pub extern "C" fn ProcessDucking(
 #[derive(Default)]
 struct DuckCompressorState {
     envelope: f32,       // Envelope follower value
+    envelope_sq: f32,    // Running mean-square, used by RMS detection mode
     gain_reduction: f32, // Current gain reduction value (1.0 = no reduction)
 }
 
@@ -357,7 +923,10 @@ pub struct ProcessingResult {
     audio_ptr: *mut f32,
     length: c_int,
     sample_rate: c_int,
+    gr_ptr: *mut f32, // Per-sample gain-reduction meter in dB; null when not produced
+    gr_length: c_int,
     _audio_data: Vec<f32>, // This field ensures the Vec memory stays alive
+    _gr_data: Vec<f32>,    // Keeps the gain-reduction meter buffer alive
 }
 
 #[no_mangle]
@@ -367,4 +936,30 @@ pub extern "C" fn FreeProcessingResult(result: *mut ProcessingResult) {
             drop(Box::from_raw(result));
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::max_true_peak;
+
+    #[test]
+    fn nyquist_alternating_signal_exceeds_sample_peak() {
+        // A signal alternating +/-0.9 every sample sits right at Nyquist.
+        // Between interior samples its reconstruction stays below the sample
+        // peak (about 0.9 * cos(pi/8) ~= 0.83), but where the alternation stops
+        // at either end of the buffer, with the edge samples held beyond it,
+        // the reconstruction overshoots (about 1.25 just after the last
+        // sample), so the true-peak detector must report something strictly
+        // greater than 0.9 here.
+        let signal: Vec<f32> = (0..64)
+            .map(|i| if i % 2 == 0 { 0.9 } else { -0.9 })
+            .collect();
+
+        let true_peak = max_true_peak(&signal);
+
+        assert!(
+            true_peak > 0.9,
+            "expected inter-sample peak above the 0.9 sample peak, got {true_peak}"
+        );
+    }
 }
\ No newline at end of file